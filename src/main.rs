@@ -1,4 +1,4 @@
-use anyhow::{Error, Result};
+use anyhow::Result;
 use array2d::Array2D;
 use crossterm::{
     event::{
@@ -8,44 +8,124 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use rand::{seq::SliceRandom, thread_rng};
+use rand::{rngs::ThreadRng, seq::SliceRandom, thread_rng};
 use ratatui::{
     backend::CrosstermBackend,
-    style::{Style, Stylize},
+    style::{Color, Style},
     text::{Line, Span},
     widgets::{Block, Borders, Paragraph},
 };
 use std::{
+    collections::HashSet,
     io::{stdout, Stdout},
-    sync::mpsc::{channel, RecvError, TryRecvError},
+    sync::mpsc::channel,
     thread::{sleep, spawn},
     time::{Duration, Instant},
 };
 
+/// The physical behaviour a [`Material`] obeys when the simulation steps it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    /// Never moves (walls, stone).
+    Solid,
+    /// Falls straight down, then diagonally when blocked (sand).
+    Powder,
+    /// Falls, then spreads sideways to equalize (water).
+    Liquid,
+    /// Occupies a cell but is displaced by anything denser.
+    Gas,
+}
+
+/// The element stored in a cell. `Empty` is the absence of material.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum Material {
+    #[default]
+    Empty,
+    Sand,
+    Water,
+    Wall,
+    Stone,
+}
+
+impl Material {
+    /// The movement rule this material obeys.
+    fn state(self) -> State {
+        match self {
+            Material::Empty => State::Gas,
+            Material::Sand => State::Powder,
+            Material::Water => State::Liquid,
+            Material::Wall | Material::Stone => State::Solid,
+        }
+    }
+
+    /// Relative weight; a cell is displaced only by a strictly denser one, so
+    /// sand (`2`) sinks through water (`1`) while water floats back up.
+    fn density(self) -> u8 {
+        match self {
+            Material::Empty => 0,
+            Material::Water => 1,
+            Material::Sand => 2,
+            Material::Stone | Material::Wall => 3,
+        }
+    }
+
+    /// The colour this material is painted with, or `None` for `Empty` so the
+    /// terminal's default shows through.
+    fn color(self) -> Option<Color> {
+        match self {
+            Material::Empty => None,
+            Material::Sand => Some(Color::LightYellow),
+            Material::Water => Some(Color::LightBlue),
+            Material::Wall => Some(Color::DarkGray),
+            Material::Stone => Some(Color::Gray),
+        }
+    }
+}
+
+/// A single grid cell: its material plus a per-frame marker set once the cell
+/// has already been processed, so moving elements aren't stepped twice.
+#[derive(Clone, Copy, Debug, Default)]
+struct Cell {
+    material: Material,
+    updated: bool,
+}
+
 #[derive(Debug)]
 struct RenderInput<'a>(Paragraph<'a>);
-impl<'a> From<&Array2D<(bool, bool)>> for RenderInput<'a> {
-    fn from(grid: &Array2D<(bool, bool)>) -> Self {
-        Self(
-            Paragraph::new(
-                grid.rows_iter()
-                    .map(|v| {
-                        v.map(|&(b, _)| {
-                            Span::styled(
-                                " ",
-                                if b {
-                                    Style::new().on_light_yellow()
-                                } else {
-                                    Style::default()
-                                },
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .into()
+impl<'a> From<&Array2D<Cell>> for RenderInput<'a> {
+    /// Collapse two grid rows into one terminal line with the upper-half-block
+    /// glyph `'▀'`: the top subcell's colour becomes the foreground and the
+    /// bottom subcell's the background, doubling effective vertical resolution.
+    fn from(grid: &Array2D<Cell>) -> Self {
+        let lines = (0..grid.column_len() / 2)
+            .map(|pair| {
+                let (top, bottom) = (pair * 2, pair * 2 + 1);
+                (0..grid.row_len())
+                    .map(|col| {
+                        match (
+                            grid[(top, col)].material.color(),
+                            grid[(bottom, col)].material.color(),
+                        ) {
+                            (None, None) => Span::raw(" "),
+                            (fg, bg) => {
+                                let mut style = Style::default();
+                                if let Some(c) = fg {
+                                    style = style.fg(c);
+                                }
+                                if let Some(c) = bg {
+                                    style = style.bg(c);
+                                }
+                                Span::styled("▀", style)
+                            }
+                        }
                     })
-                    .collect::<Vec<Line>>(),
-            )
-            .block(Block::default().title("Falling Sand").borders(Borders::ALL)),
+                    .collect::<Vec<_>>()
+                    .into()
+            })
+            .collect::<Vec<Line>>();
+        Self(
+            Paragraph::new(lines)
+                .block(Block::default().title("Falling Sand").borders(Borders::ALL)),
         )
     }
 }
@@ -70,17 +150,184 @@ impl Drop for Terminal {
 
 #[derive(Debug)]
 enum Signal {
-    Click(usize, usize),
-    Moved(usize, usize),
+    Draw(usize, usize),
+    Erase(usize, usize),
+    Release,
+    Radius(i32),
     Resize(usize, usize),
+    Select(Material),
     Break,
 }
 
+/// Move the material held at `a` into `b`, swapping their contents, and mark
+/// both cells as processed this frame.
+fn swap(grid: &mut Array2D<Cell>, a: (usize, usize), b: (usize, usize)) {
+    let tmp = grid[a];
+    grid[a] = grid[b];
+    grid[b] = tmp;
+    grid[a].updated = true;
+    grid[b].updated = true;
+}
+
+/// Apply the displacement rule for the material at `(row, col)`. Reads the
+/// cell's [`Material::state`] and moves it according to the matching rule,
+/// leaving solids and gases in place. Returns `true` if the cell moved, so the
+/// caller can keep its neighbourhood active.
+fn step_cell(grid: &mut Array2D<Cell>, row: usize, col: usize, rng: &mut ThreadRng) -> bool {
+    let cell = grid[(row, col)];
+    // Already moved this frame, or resting on the floor the border hides.
+    if cell.updated || row >= grid.column_len() - 3 {
+        return false;
+    }
+    let density = cell.material.density();
+    let cols = grid.row_len();
+    match cell.material.state() {
+        State::Powder => {
+            if grid[(row + 1, col)].material.density() < density {
+                swap(grid, (row, col), (row + 1, col));
+                return true;
+            }
+            let mut dirs = [-1i32, 1];
+            dirs.shuffle(rng);
+            for d in dirs {
+                let nc = col as i32 + d;
+                if nc < 0 || nc as usize >= cols {
+                    continue;
+                }
+                let nc = nc as usize;
+                if grid[(row, nc)].material.density() < density
+                    && grid[(row + 1, nc)].material.density() < density
+                {
+                    swap(grid, (row, col), (row + 1, nc));
+                    return true;
+                }
+            }
+        }
+        State::Liquid => {
+            if grid[(row + 1, col)].material.density() < density {
+                swap(grid, (row, col), (row + 1, col));
+                return true;
+            }
+            let mut dirs = [-1i32, 1];
+            dirs.shuffle(rng);
+            for d in dirs {
+                let nc = col as i32 + d;
+                if nc < 0 || nc as usize >= cols {
+                    continue;
+                }
+                let nc = nc as usize;
+                if grid[(row + 1, nc)].material.density() < density {
+                    swap(grid, (row, col), (row + 1, nc));
+                    return true;
+                }
+            }
+            for d in dirs {
+                let nc = col as i32 + d;
+                if nc < 0 || nc as usize >= cols {
+                    continue;
+                }
+                let nc = nc as usize;
+                if grid[(row, nc)].material.density() < density {
+                    swap(grid, (row, col), (row, nc));
+                    return true;
+                }
+            }
+        }
+        State::Solid | State::Gas => {}
+    }
+    false
+}
+
+/// Mark `(row, col)` and the cells around it active, so a change there wakes any
+/// material that was resting on or beside it. The box runs from one row above to
+/// two rows below and two columns either side, enough to cover a one-step
+/// diagonal move and its landing neighbours.
+fn activate(grid: &Array2D<Cell>, active: &mut HashSet<(usize, usize)>, row: usize, col: usize) {
+    for r in row.saturating_sub(1)..=row + 2 {
+        for c in col.saturating_sub(2)..=col + 2 {
+            if r < grid.column_len() && c < grid.row_len() {
+                active.insert((r, c));
+            }
+        }
+    }
+}
+
+/// Paint a filled disc of `radius` around `(row, col)`. Depositing a material
+/// only fills empty cells; erasing (`Material::Empty`) always clears. Touched
+/// cells are added to `active` so the next tick steps them. Points outside the
+/// grid are clamped away.
+fn paint_disc(
+    grid: &mut Array2D<Cell>,
+    active: &mut HashSet<(usize, usize)>,
+    row: i32,
+    col: i32,
+    radius: i32,
+    material: Material,
+) {
+    for dr in -radius..=radius {
+        for dc in -radius..=radius {
+            if dr * dr + dc * dc > radius * radius {
+                continue;
+            }
+            let (r, c) = (row + dr, col + dc);
+            if r < 0 || c < 0 {
+                continue;
+            }
+            let (r, c) = (r as usize, c as usize);
+            let paint = matches!(
+                grid.get(r, c),
+                Some(cell) if material == Material::Empty || cell.material == Material::Empty
+            );
+            if paint {
+                grid[(r, c)].material = material;
+                activate(grid, active, r, c);
+            }
+        }
+    }
+}
+
+/// Stroke a brush of `radius` from `(r0, c0)` to `(r1, c1)`, interpolating the
+/// gaps with Bresenham's line algorithm so fast drags stay continuous.
+fn paint_line(
+    grid: &mut Array2D<Cell>,
+    active: &mut HashSet<(usize, usize)>,
+    (r0, c0): (usize, usize),
+    (r1, c1): (usize, usize),
+    radius: i32,
+    material: Material,
+) {
+    let (mut r, mut c) = (r0 as i32, c0 as i32);
+    let (r1, c1) = (r1 as i32, c1 as i32);
+    let dr = (r1 - r).abs();
+    let dc = -(c1 - c).abs();
+    let sr = if r < r1 { 1 } else { -1 };
+    let sc = if c < c1 { 1 } else { -1 };
+    let mut e = dr + dc;
+    loop {
+        paint_disc(grid, active, r, c, radius, material);
+        if r == r1 && c == c1 {
+            break;
+        }
+        let e2 = 2 * e;
+        if e2 >= dc {
+            e += dc;
+            r += sr;
+        }
+        if e2 <= dr {
+            e += dr;
+            c += sc;
+        }
+    }
+}
+
 fn main() -> Result<()> {
     let (event_tx, event_rx) = channel::<Signal>();
     let (render_tx, render_rx) = channel::<RenderInput>();
 
     spawn(move || -> Result<()> {
+        // Which button, if any, is currently held; drives whether cursor motion
+        // paints and with which brush.
+        let mut held = None::<MouseButton>;
         loop {
             match read()? {
                 Event::Key(KeyEvent {
@@ -89,103 +336,155 @@ fn main() -> Result<()> {
                     modifiers: KeyModifiers::CONTROL,
                     ..
                 }) => event_tx.send(Signal::Break)?,
-                Event::Mouse(MouseEvent {
-                    kind: MouseEventKind::Down(MouseButton::Left),
-                    column,
-                    row,
+                Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    code: KeyCode::Char(c @ '1'..='5'),
                     ..
-                }) => event_tx.send(Signal::Click(row.into(), column.into()))?,
-                Event::Mouse(MouseEvent {
-                    kind: MouseEventKind::Moved,
-                    column,
-                    row,
+                }) => {
+                    let material = match c {
+                        '1' => Material::Sand,
+                        '2' => Material::Water,
+                        '3' => Material::Wall,
+                        '4' => Material::Stone,
+                        _ => Material::Empty,
+                    };
+                    event_tx.send(Signal::Select(material))?
+                }
+                Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    code: KeyCode::Char('+' | '='),
+                    ..
+                }) => event_tx.send(Signal::Radius(1))?,
+                Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    code: KeyCode::Char('-'),
                     ..
-                }) => event_tx.send(Signal::Moved(row.into(), column.into()))?,
+                }) => event_tx.send(Signal::Radius(-1))?,
+                Event::Mouse(MouseEvent {
+                    kind, column, row, ..
+                }) => {
+                    let (row, col) = (row.into(), column.into());
+                    match kind {
+                        MouseEventKind::Down(button) | MouseEventKind::Drag(button) => {
+                            held = Some(button);
+                            match button {
+                                MouseButton::Left => event_tx.send(Signal::Draw(row, col))?,
+                                _ => event_tx.send(Signal::Erase(row, col))?,
+                            }
+                        }
+                        MouseEventKind::Moved => match held {
+                            Some(MouseButton::Left) => event_tx.send(Signal::Draw(row, col))?,
+                            Some(_) => event_tx.send(Signal::Erase(row, col))?,
+                            None => continue,
+                        },
+                        MouseEventKind::Up(_) => {
+                            held = None;
+                            event_tx.send(Signal::Release)?
+                        }
+                        _ => continue,
+                    }
+                }
                 Event::Resize(x, y) => event_tx.send(Signal::Resize(x.into(), y.into()))?,
                 _ => continue,
             };
         }
     });
 
+    // Restore the terminal before the default hook prints, so a panic in
+    // either worker thread leaves a readable backtrace instead of a mangled
+    // raw-mode alternate screen.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture);
+        let _ = disable_raw_mode();
+        hook(info);
+    }));
+
     let mut terminal = Terminal::init()?;
+    // Two grid rows render into one terminal line (see `RenderInput`), so the
+    // simulation runs at twice the terminal's height.
     let mut grid = Array2D::filled_with(
-        (false, false),
-        terminal.0.size()?.height.into(),
+        Cell::default(),
+        usize::from(terminal.0.size()?.height) * 2,
         terminal.0.size()?.width.into(),
     );
 
     spawn(move || -> Result<()> {
-        let mut sand_spawner = None::<(usize, usize)>;
+        // Endpoint of the last brush stroke, used to interpolate drags; reset
+        // when the button is released.
+        let mut last = None::<(usize, usize)>;
+        let mut selected = Material::Sand;
+        let mut radius = 0i32;
         let mut rng = thread_rng();
+        // Cells worth stepping this tick: everything that moved last frame plus
+        // its neighbours, and anything the brush just touched. Empty, at-rest
+        // regions drop out, so per-tick work tracks the moving material.
+        let mut active = HashSet::<(usize, usize)>::new();
         Ok('main: loop {
             let clk_start = Instant::now();
             let mut events = event_rx.try_iter();
             while let Some(event) = events.next() {
                 match event {
-                    Signal::Click(row, col) => {
-                        sand_spawner = if sand_spawner.is_none() {
-                            Some((row, col))
-                        } else {
-                            None
-                        }
+                    Signal::Draw(row, col) => {
+                        // A terminal line spans two grid rows; aim at its top subcell.
+                        let to = (row * 2, col);
+                        paint_line(&mut grid, &mut active, last.unwrap_or(to), to, radius, selected);
+                        last = Some(to);
                     }
-                    Signal::Moved(row, col) => {
-                        sand_spawner = sand_spawner.map(|_| (row, col));
+                    Signal::Erase(row, col) => {
+                        let to = (row * 2, col);
+                        paint_line(
+                            &mut grid,
+                            &mut active,
+                            last.unwrap_or(to),
+                            to,
+                            radius,
+                            Material::Empty,
+                        );
+                        last = Some(to);
                     }
-                    Signal::Resize(_, _) => todo!(),
-                    Signal::Break => break 'main,
-                }
-            }
-            for row in 0..grid.column_len() {
-                for col in 0..grid.row_len() {
-                    let (curr, down, left, right) = (
-                        grid.get(row, col).copied(),
-                        grid.get(row + 1, col).copied(),
-                        if col > 0 {
-                            grid.get(row + 1, col - 1).copied()
-                        } else {
-                            None
-                        },
-                        grid.get(row + 1, col + 1).copied(),
-                    );
-                    match (curr, down, left, right) {
-                        (Some((false, false)), ..) if sand_spawner == Some((row, col)) => {
-                            grid[(row, col)] = (true, true);
+                    Signal::Release => last = None,
+                    Signal::Radius(delta) => radius = (radius + delta).clamp(0, 20),
+                    Signal::Select(material) => selected = material,
+                    Signal::Resize(cols, rows) => {
+                        let rows = rows * 2;
+                        let mut next = Array2D::filled_with(Cell::default(), rows, cols);
+                        for r in 0..grid.column_len().min(rows) {
+                            for c in 0..grid.row_len().min(cols) {
+                                next[(r, c)] = grid[(r, c)];
+                            }
                         }
-                        (Some((true, false)), Some((false, _)), ..)
-                            if row < grid.column_len() - 3 =>
-                        {
-                            grid[(row, col)] = (false, true);
-                            grid[(row + 1, col)] = (true, true);
+                        grid = next;
+                        last = None;
+                        // Bounds moved; rewake every cell holding material.
+                        active.clear();
+                        for row in 0..grid.column_len() {
+                            for col in 0..grid.row_len() {
+                                if grid[(row, col)].material != Material::Empty {
+                                    activate(&grid, &mut active, row, col);
+                                }
+                            }
                         }
-                        (Some((true, _)), Some((true, _)), Some((false, _)), Some((false, _)))
-                            if row < grid.column_len() - 3 =>
-                        {
-                            grid[(row, col)] = (false, true);
-                            grid[(row + 1, *[col - 1, col + 1].choose(&mut rng).unwrap())] =
-                                (true, true);
-                        }
-                        (Some((true, _)), Some((true, _)), Some((true, _)), Some((false, _)))
-                            if row < grid.column_len() - 3 =>
-                        {
-                            grid[(row, col)] = (false, true);
-                            grid[(row + 1, col + 1)] = (true, true);
-                        }
-                        (Some((true, _)), Some((true, _)), Some((false, _)), Some((true, _)))
-                            if row < grid.column_len() - 3 =>
-                        {
-                            grid[(row, col)] = (false, true);
-                            grid[(row + 1, col - 1)] = (true, true);
-                        }
-                        _ => (),
                     }
+                    Signal::Break => break 'main,
                 }
             }
-            for row in 0..grid.column_len() {
-                for col in 0..grid.row_len() {
-                    grid[(row, col)].1 = false;
+            // Step only the active cells, top-to-bottom (a cell that moved is
+            // marked and not stepped again, so material moves at most one step
+            // per tick), and collect the wake set for the next tick.
+            let mut cells = active.iter().copied().collect::<Vec<_>>();
+            cells.sort_unstable();
+            let mut next = HashSet::<(usize, usize)>::new();
+            for (row, col) in cells {
+                if step_cell(&mut grid, row, col, &mut rng) {
+                    activate(&grid, &mut next, row, col);
                 }
             }
+            // Clear the per-frame marker on every cell we touched this tick.
+            for &(row, col) in active.iter().chain(next.iter()) {
+                grid[(row, col)].updated = false;
+            }
+            active = next;
             let delta = Duration::from_millis(16).checked_sub(clk_start.elapsed());
             delta.and_then(|d| Some(sleep(d)));
             render_tx.send((&grid).into())?;